@@ -1,81 +1,276 @@
-use std::collections::HashMap;
-use std::fs;
-use std::path::Path;
-use anyhow::Result;
-use serde::{Deserialize, Serialize};
-use tokio::sync::RwLock;
-use tracing::debug;
-
-#[derive(Debug, Serialize, Deserialize, Default, Clone)]
+// chat_history.rs
+
+use anyhow::{Context, Result};
+use rusqlite::{params, Connection, OptionalExtension};
+use std::sync::{Arc, Mutex};
+use tokio::task;
+use tracing::{debug, error};
+
+#[derive(Debug, Clone)]
 pub struct ChatMessage {
     pub role: String,
     pub content: String,
     pub timestamp: i64,
 }
 
-#[derive(Debug, Serialize, Deserialize, Default)]
-pub struct UserHistory {
-    messages: Vec<ChatMessage>,
-}
-
-#[derive(Debug, Default)]
+/// Persists chat history and per-user persona selection in a SQLite database.
+/// All DB access goes through `spawn_blocking` since `rusqlite::Connection` is
+/// blocking I/O and not safe to hold across `.await` points.
 pub struct ChatHistoryManager {
-    histories: RwLock<HashMap<String, UserHistory>>,
-    storage_path: String,
+    conn: Arc<Mutex<Connection>>,
 }
 
 impl ChatHistoryManager {
-    pub fn new(storage_path: &str) -> Self {
-        Self {
-            histories: RwLock::new(HashMap::new()),
-            storage_path: storage_path.to_string(),
-        }
+    pub fn new(db_path: &str) -> Result<Self> {
+        let conn = Connection::open(db_path)
+            .with_context(|| format!("Failed to open chat history database: {}", db_path))?;
+        Self::from_connection(conn)
     }
 
-    pub async fn load_histories(&self) -> Result<()> {
-        let path = Path::new(&self.storage_path);
-        if !path.exists() {
-            fs::create_dir_all(path)?;
-            return Ok(());
-        }
+    fn from_connection(conn: Connection) -> Result<Self> {
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS messages (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                user_id TEXT NOT NULL,
+                role TEXT NOT NULL,
+                content TEXT NOT NULL,
+                timestamp INTEGER NOT NULL
+            );
+            CREATE INDEX IF NOT EXISTS idx_messages_user_id ON messages(user_id);
+
+            CREATE TABLE IF NOT EXISTS user_personas (
+                user_id TEXT PRIMARY KEY,
+                persona TEXT NOT NULL
+            );
+
+            CREATE TABLE IF NOT EXISTS user_dialogue_state (
+                user_id TEXT PRIMARY KEY,
+                state TEXT NOT NULL
+            );",
+        )
+        .context("Failed to initialize chat history schema")?;
+
+        Ok(Self {
+            conn: Arc::new(Mutex::new(conn)),
+        })
+    }
+
+    pub async fn add_message(&self, user_id: &str, message: ChatMessage) -> Result<()> {
+        let conn = Arc::clone(&self.conn);
+        let user_id_owned = user_id.to_string();
+
+        task::spawn_blocking(move || -> Result<()> {
+            let conn = conn.lock().unwrap();
+            conn.execute(
+                "INSERT INTO messages (user_id, role, content, timestamp) VALUES (?1, ?2, ?3, ?4)",
+                params![user_id_owned, message.role, message.content, message.timestamp],
+            )?;
+            Ok(())
+        })
+        .await
+        .context("add_message task panicked")??;
+
+        debug!("Added message for user {}", user_id);
+        Ok(())
+    }
+
+    /// Returns the last `limit` messages for a user, oldest first, so prompts
+    /// stay bounded instead of growing with the whole transcript.
+    pub async fn get_recent(&self, user_id: &str, limit: i64) -> Vec<ChatMessage> {
+        self.query_messages(user_id, limit).await
+    }
+
+    async fn query_messages(&self, user_id: &str, limit: i64) -> Vec<ChatMessage> {
+        let conn = Arc::clone(&self.conn);
+        let user_id_owned = user_id.to_string();
+
+        let result = task::spawn_blocking(move || -> rusqlite::Result<Vec<ChatMessage>> {
+            let conn = conn.lock().unwrap();
+            let mut stmt = conn.prepare(
+                "SELECT role, content, timestamp FROM messages
+                 WHERE user_id = ?1
+                 ORDER BY id DESC
+                 LIMIT ?2",
+            )?;
+            let rows = stmt.query_map(
+                params![user_id_owned, limit],
+                |row| {
+                    Ok(ChatMessage {
+                        role: row.get(0)?,
+                        content: row.get(1)?,
+                        timestamp: row.get(2)?,
+                    })
+                },
+            )?;
+
+            let mut messages = rows.collect::<rusqlite::Result<Vec<_>>>()?;
+            messages.reverse();
+            Ok(messages)
+        })
+        .await;
 
-        let mut histories = self.histories.write().await;
-        for entry in fs::read_dir(path)? {
-            let entry = entry?;
-            let path = entry.path();
-            if path.extension().and_then(|s| s.to_str()) == Some("json") {
-                if let Some(user_id) = path.file_stem().and_then(|s| s.to_str()) {
-                    let content = fs::read_to_string(&path)?;
-                    let history: UserHistory = serde_json::from_str(&content)?;
-                    histories.insert(user_id.to_string(), history);
-                }
+        match result {
+            Ok(Ok(messages)) => {
+                debug!("Retrieved {} messages for user {}", messages.len(), user_id);
+                messages
+            }
+            Ok(Err(e)) => {
+                error!("Failed to query history for user {}: {:?}", user_id, e);
+                Vec::new()
+            }
+            Err(e) => {
+                error!("get_recent task panicked for user {}: {:?}", user_id, e);
+                Vec::new()
             }
         }
+    }
+
+    pub async fn get_persona(&self, user_id: &str) -> Option<String> {
+        let conn = Arc::clone(&self.conn);
+        let user_id_owned = user_id.to_string();
+
+        task::spawn_blocking(move || -> rusqlite::Result<Option<String>> {
+            let conn = conn.lock().unwrap();
+            conn.query_row(
+                "SELECT persona FROM user_personas WHERE user_id = ?1",
+                params![user_id_owned],
+                |row| row.get(0),
+            )
+            .optional()
+        })
+        .await
+        .expect("get_persona task panicked")
+        .unwrap_or_else(|e| {
+            error!("Failed to read persona: {:?}", e);
+            None
+        })
+    }
+
+    pub async fn set_persona(&self, user_id: &str, persona: &str) -> Result<()> {
+        let conn = Arc::clone(&self.conn);
+        let user_id_owned = user_id.to_string();
+        let persona_owned = persona.to_string();
+
+        task::spawn_blocking(move || -> Result<()> {
+            let conn = conn.lock().unwrap();
+            conn.execute(
+                "INSERT INTO user_personas (user_id, persona) VALUES (?1, ?2)
+                 ON CONFLICT(user_id) DO UPDATE SET persona = excluded.persona",
+                params![user_id_owned, persona_owned],
+            )?;
+            Ok(())
+        })
+        .await
+        .context("set_persona task panicked")??;
+
+        debug!("Set persona for user {} to {}", user_id, persona);
         Ok(())
     }
 
-    pub async fn add_message(&self, user_id: &str, message: ChatMessage) -> Result<()> {
-        let mut histories = self.histories.write().await;
-        let history = histories.entry(user_id.to_string()).or_default();
-        history.messages.push(message.clone());
-        debug!("Added message for user {}: {:?}", user_id, message);
-        
-        // Save to file
-        let path = Path::new(&self.storage_path).join(format!("{}.json", user_id));
-        let content = serde_json::to_string_pretty(&history)?;
-        fs::write(&path, &content)?;
-        debug!("Saved history to file: {:?}", path);
-        
+    /// Raw (JSON-serialized) dialogue state for a user, if any is stored.
+    /// `dialogue` deserializes this into a `DialogueState`.
+    pub async fn get_dialogue_state(&self, user_id: &str) -> Option<String> {
+        let conn = Arc::clone(&self.conn);
+        let user_id_owned = user_id.to_string();
+
+        task::spawn_blocking(move || -> rusqlite::Result<Option<String>> {
+            let conn = conn.lock().unwrap();
+            conn.query_row(
+                "SELECT state FROM user_dialogue_state WHERE user_id = ?1",
+                params![user_id_owned],
+                |row| row.get(0),
+            )
+            .optional()
+        })
+        .await
+        .expect("get_dialogue_state task panicked")
+        .unwrap_or_else(|e| {
+            error!("Failed to read dialogue state: {:?}", e);
+            None
+        })
+    }
+
+    pub async fn set_dialogue_state(&self, user_id: &str, state_json: &str) -> Result<()> {
+        let conn = Arc::clone(&self.conn);
+        let user_id_owned = user_id.to_string();
+        let state_owned = state_json.to_string();
+
+        task::spawn_blocking(move || -> Result<()> {
+            let conn = conn.lock().unwrap();
+            conn.execute(
+                "INSERT INTO user_dialogue_state (user_id, state) VALUES (?1, ?2)
+                 ON CONFLICT(user_id) DO UPDATE SET state = excluded.state",
+                params![user_id_owned, state_owned],
+            )?;
+            Ok(())
+        })
+        .await
+        .context("set_dialogue_state task panicked")??;
+
         Ok(())
     }
 
-    pub async fn get_history(&self, user_id: &str) -> Vec<ChatMessage> {
-        let histories = self.histories.read().await;
-        let history = histories
-            .get(user_id)
-            .map(|h| h.messages.clone())
-            .unwrap_or_default();
-        debug!("Retrieved {} messages for user {}", history.len(), user_id);
-        history
+    pub async fn clear_dialogue_state(&self, user_id: &str) -> Result<()> {
+        let conn = Arc::clone(&self.conn);
+        let user_id_owned = user_id.to_string();
+
+        task::spawn_blocking(move || -> Result<()> {
+            let conn = conn.lock().unwrap();
+            conn.execute(
+                "DELETE FROM user_dialogue_state WHERE user_id = ?1",
+                params![user_id_owned],
+            )?;
+            Ok(())
+        })
+        .await
+        .context("clear_dialogue_state task panicked")??;
+
+        debug!("Cleared dialogue state for user {}", user_id);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn in_memory_manager() -> ChatHistoryManager {
+        ChatHistoryManager::from_connection(Connection::open_in_memory().unwrap()).unwrap()
+    }
+
+    fn message(role: &str, content: &str, timestamp: i64) -> ChatMessage {
+        ChatMessage {
+            role: role.to_string(),
+            content: content.to_string(),
+            timestamp,
+        }
+    }
+
+    #[tokio::test]
+    async fn get_recent_returns_only_the_last_n_messages_oldest_first() {
+        let manager = in_memory_manager();
+        for i in 0..5 {
+            manager
+                .add_message("user1", message("user", &format!("msg{}", i), i))
+                .await
+                .unwrap();
+        }
+
+        let recent = manager.get_recent("user1", 3).await;
+
+        let contents: Vec<&str> = recent.iter().map(|m| m.content.as_str()).collect();
+        assert_eq!(contents, vec!["msg2", "msg3", "msg4"]);
+    }
+
+    #[tokio::test]
+    async fn get_recent_only_returns_the_requesting_users_messages() {
+        let manager = in_memory_manager();
+        manager.add_message("user1", message("user", "hello", 0)).await.unwrap();
+        manager.add_message("user2", message("user", "hi", 0)).await.unwrap();
+
+        let recent = manager.get_recent("user1", 10).await;
+
+        assert_eq!(recent.len(), 1);
+        assert_eq!(recent[0].content, "hello");
     }
-} 
\ No newline at end of file
+}
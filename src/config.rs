@@ -0,0 +1,49 @@
+// config.rs
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::fs;
+use std::path::Path;
+
+/// A single named persona the bot can be switched to via `/role`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct RoleConfig {
+    pub name: String,
+    pub preamble: String,
+    pub model: Option<String>,
+    pub temperature: Option<f64>,
+    pub max_tokens: Option<u64>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Config {
+    pub default_role: String,
+    pub roles: Vec<RoleConfig>,
+}
+
+impl Config {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let content = fs::read_to_string(path.as_ref())
+            .with_context(|| format!("Failed to read config file: {:?}", path.as_ref()))?;
+        let config: Config = toml::from_str(&content)
+            .with_context(|| format!("Failed to parse config file: {:?}", path.as_ref()))?;
+
+        if config.role(&config.default_role).is_none() {
+            anyhow::bail!(
+                "default_role '{}' does not match any configured role",
+                config.default_role
+            );
+        }
+
+        Ok(config)
+    }
+
+    pub fn role(&self, name: &str) -> Option<&RoleConfig> {
+        self.roles.iter().find(|role| role.name == name)
+    }
+
+    pub fn default_role(&self) -> &RoleConfig {
+        self.role(&self.default_role)
+            .expect("default_role was validated at load time")
+    }
+}
@@ -0,0 +1,197 @@
+// dialogue.rs
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use crate::rig_agent::RigAgent;
+
+/// Steps of the guided portfolio-review wizard.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub enum PortfolioReviewStep {
+    AwaitingChain,
+    AwaitingToken { chain: String },
+    AwaitingAmount { chain: String, token: String },
+}
+
+/// A user's place in a multi-step conversation. `FreeChat` is the default:
+/// every message is an independent one-shot prompt handled by
+/// `RigAgent::process_message`. Any other variant is a guided flow driven by
+/// `advance`.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Default)]
+pub enum DialogueState {
+    #[default]
+    FreeChat,
+    PortfolioReview(PortfolioReviewStep),
+}
+
+impl DialogueState {
+    pub fn start_portfolio_review() -> Self {
+        DialogueState::PortfolioReview(PortfolioReviewStep::AwaitingChain)
+    }
+
+    fn from_json(raw: &str) -> Self {
+        serde_json::from_str(raw).unwrap_or_default()
+    }
+
+    fn to_json(&self) -> Result<String> {
+        serde_json::to_string(self).context("Failed to serialize dialogue state")
+    }
+
+    /// Transitions that don't need to call out to the model, split out from
+    /// `advance` so they're unit-testable without a real `RigAgent`. Returns
+    /// `None` for `FreeChat` and `AwaitingAmount`, which `advance` handles
+    /// itself.
+    fn advance_pure(&self, content: &str) -> Option<(Self, String)> {
+        match self {
+            DialogueState::PortfolioReview(PortfolioReviewStep::AwaitingChain) => {
+                let chain = content.trim().to_string();
+                let next = DialogueState::PortfolioReview(PortfolioReviewStep::AwaitingToken { chain });
+                Some((next, "Got it. Which token?".to_string()))
+            }
+            DialogueState::PortfolioReview(PortfolioReviewStep::AwaitingToken { chain }) => {
+                let token = content.trim().to_string();
+                let next = DialogueState::PortfolioReview(PortfolioReviewStep::AwaitingAmount {
+                    chain: chain.clone(),
+                    token,
+                });
+                Some((next, "And how much of it are you holding?".to_string()))
+            }
+            _ => None,
+        }
+    }
+
+    /// Runs one step of the dialogue for an incoming message, returning the
+    /// next state and the reply to send. Must not be called on `FreeChat`;
+    /// callers should fall back to `RigAgent::process_message` for that case.
+    async fn advance(self, content: &str, rig_agent: &RigAgent, user_id: &str) -> Result<(Self, String)> {
+        if let Some(result) = self.advance_pure(content) {
+            return Ok(result);
+        }
+
+        match self {
+            DialogueState::FreeChat => unreachable!("FreeChat has no transition; handled by the caller"),
+            DialogueState::PortfolioReview(PortfolioReviewStep::AwaitingAmount { chain, token }) => {
+                let amount = content.trim().to_string();
+                let prompt = format!(
+                    "Give a quick portfolio-review style take on holding {} {} on {}.",
+                    amount, token, chain
+                );
+                let response = rig_agent.process_message(user_id, &prompt, &[]).await?;
+                Ok((DialogueState::FreeChat, response))
+            }
+            DialogueState::PortfolioReview(PortfolioReviewStep::AwaitingChain)
+            | DialogueState::PortfolioReview(PortfolioReviewStep::AwaitingToken { .. }) => {
+                unreachable!("advance_pure handles these variants")
+            }
+        }
+    }
+}
+
+impl RigAgent {
+    /// Loads `user_id`'s dialogue state, advances it by one step if they're
+    /// mid-flow, and persists the result. Falls through to
+    /// `process_message` when the user is in (or returns to) free chat.
+    pub async fn advance_dialogue(
+        &self,
+        user_id: &str,
+        content: &str,
+        attachments: &[crate::rig_agent::IncomingAttachment],
+    ) -> Result<String> {
+        let state = match self.history_manager.get_dialogue_state(user_id).await {
+            Some(raw) => DialogueState::from_json(&raw),
+            None => DialogueState::FreeChat,
+        };
+
+        match state {
+            DialogueState::FreeChat => self.process_message(user_id, content, attachments).await,
+            other => {
+                let (next, reply) = other.advance(content, self, user_id).await?;
+                self.history_manager
+                    .set_dialogue_state(user_id, &next.to_json()?)
+                    .await?;
+                Ok(reply)
+            }
+        }
+    }
+
+    /// Starts the portfolio-review wizard for `user_id`, returning the first
+    /// prompt to send back.
+    pub async fn start_portfolio_review(&self, user_id: &str) -> Result<String> {
+        let state = DialogueState::start_portfolio_review();
+        self.history_manager
+            .set_dialogue_state(user_id, &state.to_json()?)
+            .await?;
+        Ok("Let's review your portfolio. Which chain is it on?".to_string())
+    }
+
+    /// Clears any in-progress dialogue, returning `user_id` to free chat.
+    pub async fn reset_dialogue(&self, user_id: &str) -> Result<()> {
+        self.history_manager.clear_dialogue_state(user_id).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn advance_pure_walks_chain_then_token_steps() {
+        let state = DialogueState::start_portfolio_review();
+
+        let (state, reply) = state.advance_pure("ethereum").expect("AwaitingChain should transition");
+        assert_eq!(reply, "Got it. Which token?");
+        assert_eq!(
+            state,
+            DialogueState::PortfolioReview(PortfolioReviewStep::AwaitingToken {
+                chain: "ethereum".to_string(),
+            })
+        );
+
+        let (state, reply) = state.advance_pure("USDC").expect("AwaitingToken should transition");
+        assert_eq!(reply, "And how much of it are you holding?");
+        assert_eq!(
+            state,
+            DialogueState::PortfolioReview(PortfolioReviewStep::AwaitingAmount {
+                chain: "ethereum".to_string(),
+                token: "USDC".to_string(),
+            })
+        );
+    }
+
+    #[test]
+    fn advance_pure_trims_whitespace_from_input() {
+        let state = DialogueState::start_portfolio_review();
+        let (state, _) = state.advance_pure("  ethereum  \n").unwrap();
+        assert_eq!(
+            state,
+            DialogueState::PortfolioReview(PortfolioReviewStep::AwaitingToken {
+                chain: "ethereum".to_string(),
+            })
+        );
+    }
+
+    #[test]
+    fn advance_pure_has_no_transition_for_free_chat_or_awaiting_amount() {
+        assert_eq!(DialogueState::FreeChat.advance_pure("anything"), None);
+
+        let awaiting_amount = DialogueState::PortfolioReview(PortfolioReviewStep::AwaitingAmount {
+            chain: "ethereum".to_string(),
+            token: "USDC".to_string(),
+        });
+        assert_eq!(awaiting_amount.advance_pure("100"), None);
+    }
+
+    #[test]
+    fn from_json_falls_back_to_free_chat_on_garbage_input() {
+        assert_eq!(DialogueState::from_json("not valid json"), DialogueState::FreeChat);
+        assert_eq!(DialogueState::from_json(""), DialogueState::FreeChat);
+    }
+
+    #[test]
+    fn to_json_and_from_json_round_trip() {
+        let state = DialogueState::PortfolioReview(PortfolioReviewStep::AwaitingToken {
+            chain: "solana".to_string(),
+        });
+        let json = state.to_json().unwrap();
+        assert_eq!(DialogueState::from_json(&json), state);
+    }
+}
@@ -0,0 +1,131 @@
+// discord_output.rs
+//
+// Splits long responses into Discord-embed-sized chunks without slicing by
+// raw byte index (which can panic on a multibyte UTF-8 boundary) and without
+// breaking in the middle of a fenced code block.
+
+use serenity::builder::CreateEmbed;
+
+/// Discord embed descriptions cap at 4096 characters; leave headroom for the
+/// continuation footer and a reopened code fence.
+const MAX_CHUNK_LEN: usize = 3800;
+
+/// Splits `text` into chunks along line boundaries, each at most `max_len`
+/// characters. If a split would fall inside a fenced code block, the fence
+/// is closed at the end of one chunk and reopened (with the same language
+/// hint) at the start of the next, so each chunk renders as valid Markdown;
+/// those added fence lines are not counted against `max_len`, so such a
+/// chunk can run past it by their length.
+/// A single line longer than `max_len` (e.g. an unbroken URL or code line)
+/// is hard-split, since no line-boundary split can make it fit otherwise.
+pub fn split_into_chunks(text: &str, max_len: usize) -> Vec<String> {
+    let mut chunks = Vec::new();
+    let mut current = String::new();
+    let mut fence_lang: Option<String> = None;
+
+    for line in text.lines() {
+        let pieces = if line.chars().count() > max_len {
+            hard_split(line, max_len)
+        } else {
+            vec![line.to_string()]
+        };
+
+        for piece in pieces {
+            if current.chars().count() + piece.chars().count() + 1 > max_len && !current.is_empty() {
+                if fence_lang.is_some() {
+                    current.push_str("```\n");
+                }
+                chunks.push(current.trim_end_matches('\n').to_string());
+                current = String::new();
+                if let Some(lang) = &fence_lang {
+                    current.push_str(&format!("```{}\n", lang));
+                }
+            }
+
+            current.push_str(&piece);
+            current.push('\n');
+        }
+
+        if line.trim_start().starts_with("```") {
+            fence_lang = match fence_lang {
+                Some(_) => None,
+                None => Some(line.trim_start().trim_start_matches("```").trim().to_string()),
+            };
+        }
+    }
+
+    if !current.trim().is_empty() {
+        chunks.push(current.trim_end_matches('\n').to_string());
+    }
+
+    if chunks.is_empty() {
+        chunks.push(String::new());
+    }
+
+    chunks
+}
+
+/// Splits a single line into `max_len`-character pieces, for a line that by
+/// itself can't fit in a chunk no matter where else we split.
+fn hard_split(line: &str, max_len: usize) -> Vec<String> {
+    line.chars()
+        .collect::<Vec<_>>()
+        .chunks(max_len)
+        .map(|piece| piece.iter().collect())
+        .collect()
+}
+
+/// Builds one embed per chunk of `text`, with a "Part i of N" continuation
+/// footer whenever the response didn't fit in a single embed.
+pub fn build_embeds(text: &str) -> Vec<CreateEmbed> {
+    let chunks = split_into_chunks(text, MAX_CHUNK_LEN);
+    let total = chunks.len();
+
+    chunks
+        .into_iter()
+        .enumerate()
+        .map(|(i, chunk)| {
+            let mut embed = CreateEmbed::default();
+            embed.description(chunk);
+            if total > 1 {
+                embed.footer(|footer| footer.text(format!("Part {} of {}", i + 1, total)));
+            }
+            embed
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_short_text_in_one_chunk() {
+        let chunks = split_into_chunks("just a short reply", 50);
+        assert_eq!(chunks, vec!["just a short reply".to_string()]);
+    }
+
+    #[test]
+    fn splits_on_line_boundaries_once_over_the_limit() {
+        let text = "aaaa\nbbbb\ncccc";
+        let chunks = split_into_chunks(text, 10);
+        assert_eq!(chunks, vec!["aaaa\nbbbb".to_string(), "cccc".to_string()]);
+    }
+
+    #[test]
+    fn hard_splits_a_single_oversized_line() {
+        let line = "x".repeat(25);
+        let chunks = split_into_chunks(&line, 10);
+        assert!(chunks.iter().all(|c| c.chars().count() <= 10));
+        assert_eq!(chunks.iter().map(|c| c.chars().count()).sum::<usize>(), 25);
+    }
+
+    #[test]
+    fn reopens_fence_with_language_hint_across_a_split() {
+        let text = "```rust\nfn a() {}\nfn b() {}\n```";
+        let chunks = split_into_chunks(text, 20);
+        assert!(chunks.len() > 1);
+        assert!(chunks[0].ends_with("```"));
+        assert!(chunks[1].starts_with("```rust"));
+    }
+}
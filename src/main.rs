@@ -2,10 +2,16 @@
 
 mod rig_agent;
 mod chat_history;
+mod config;
+mod dialogue;
+mod discord_output;
+mod url_context;
 
 use anyhow::Result;
+use futures::StreamExt;
 use serenity::async_trait;
 use serenity::model::application::command::Command;
+use serenity::model::application::interaction::application_command::ApplicationCommandInteraction;
 use serenity::model::application::interaction::Interaction;
 use serenity::model::gateway::Ready;
 use serenity::model::channel::Message;
@@ -13,8 +19,9 @@ use serenity::prelude::*;
 use serenity::model::application::command::CommandOptionType;
 use std::env;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tracing::{error, info, debug};
-use rig_agent::RigAgent;
+use rig_agent::{IncomingAttachment, RigAgent};
 use dotenv::dotenv;
 
 // Define a key for storing the bot's user ID in the TypeMap
@@ -28,6 +35,100 @@ struct Handler {
     rig_agent: Arc<RigAgent>,
 }
 
+/// How often (at minimum) the streaming `/ask` reply is edited in place,
+/// besides every 1500 characters of text accumulated since the last edit.
+const STREAM_EDIT_INTERVAL: Duration = Duration::from_secs(1);
+const STREAM_EDIT_CHAR_THRESHOLD: usize = 1500;
+
+/// Discord message `content` (including interaction-response edits) is
+/// capped at 2000 characters. Live edits show only the trailing window of
+/// the accumulated text so they never exceed that cap while the response is
+/// still streaming; the full text still gets its own properly chunked
+/// embeds once the stream ends.
+const STREAM_EDIT_WINDOW_LEN: usize = 1900;
+
+/// Returns the last `max_len` characters of `text`, prefixed with an
+/// ellipsis if anything was cut off, for a live in-progress preview.
+fn tail_window(text: &str, max_len: usize) -> String {
+    let char_count = text.chars().count();
+    if char_count <= max_len {
+        return text.to_string();
+    }
+    let tail: String = text.chars().skip(char_count - max_len).collect();
+    format!("…{}", tail)
+}
+
+impl Handler {
+    /// Streams `/ask`'s response, editing the deferred interaction reply in
+    /// place every ~1s or ~1500 characters instead of blocking for the full
+    /// completion. Avoids the 15-minute interaction timeout on slow answers.
+    async fn stream_ask_response(&self, ctx: &Context, command: &ApplicationCommandInteraction, query: &str) {
+        let user_id = command.user.id.to_string();
+
+        let stream = match self.rig_agent.process_message_streaming(&user_id, query).await {
+            Ok(stream) => stream,
+            Err(e) => {
+                error!("Error starting streaming response: {:?}", e);
+                if let Err(why) = command.edit_original_interaction_response(&ctx.http, |response| {
+                    response.content(format!("Error processing request: {:?}", e))
+                }).await {
+                    error!("Cannot send error response: {:?}", why);
+                }
+                return;
+            }
+        };
+        let mut stream = Box::pin(stream);
+
+        let mut accumulated = String::new();
+        let mut last_edit = Instant::now();
+        let mut last_edit_len = 0;
+
+        while let Some(chunk) = stream.next().await {
+            match chunk {
+                Ok(delta) => {
+                    accumulated.push_str(&delta);
+                    if last_edit.elapsed() >= STREAM_EDIT_INTERVAL
+                        || accumulated.len() - last_edit_len >= STREAM_EDIT_CHAR_THRESHOLD
+                    {
+                        let preview = tail_window(&accumulated, STREAM_EDIT_WINDOW_LEN);
+                        if let Err(why) = command.edit_original_interaction_response(&ctx.http, |response| {
+                            response.content(preview)
+                        }).await {
+                            error!("Cannot update streaming response: {:?}", why);
+                        }
+                        last_edit = Instant::now();
+                        last_edit_len = accumulated.len();
+                    }
+                }
+                Err(e) => {
+                    error!("Error in streaming response: {:?}", e);
+                    break;
+                }
+            }
+        }
+
+        let mut embeds = discord_output::build_embeds(&accumulated).into_iter();
+        if let Some(first) = embeds.next() {
+            if let Err(why) = command.edit_original_interaction_response(&ctx.http, |response| {
+                response.content("").set_embed(first)
+            }).await {
+                error!("Cannot send final streaming response: {:?}", why);
+            }
+        }
+        for embed in embeds {
+            if let Err(why) = command.create_followup_message(&ctx.http, |message| {
+                message.set_embed(embed)
+            }).await {
+                error!("Cannot send follow-up chunk: {:?}", why);
+            }
+        }
+
+        if let Err(e) = self.rig_agent.record_exchange(&user_id, query, &accumulated).await {
+            error!("Error recording exchange: {:?}", e);
+        }
+    }
+}
+
 #[async_trait]
 impl EventHandler for Handler {
     async fn interaction_create(&self, ctx: Context, interaction: Interaction) {
@@ -41,22 +142,58 @@ impl EventHandler for Handler {
                 return;
             }
 
+            if command.data.name.as_str() == "ask" {
+                let query = command
+                    .data
+                    .options
+                    .get(0)
+                    .and_then(|opt| opt.value.as_ref())
+                    .and_then(|v| v.as_str())
+                    .unwrap_or("What would you like to ask?")
+                    .to_string();
+                debug!("Query: {}", query);
+
+                self.stream_ask_response(&ctx, &command, &query).await;
+                return;
+            }
+
             let content = match command.data.name.as_str() {
                 "hello" => "Hello! I'm your helpful Rust and Rig-powered assistant. How can I assist you today?".to_string(),
-                "ask" => {
-                    let query = command
+                "role" => {
+                    let role_name = command
                         .data
                         .options
                         .get(0)
                         .and_then(|opt| opt.value.as_ref())
                         .and_then(|v| v.as_str())
-                        .unwrap_or("What would you like to ask?");
-                    debug!("Query: {}", query);
-                    match self.rig_agent.process_message(&command.user.id.to_string(), query).await {
+                        .unwrap_or("");
+                    match self.rig_agent.set_role(&command.user.id.to_string(), role_name).await {
+                        Ok(()) => format!("Switched to the **{}** persona.", role_name),
+                        Err(e) => {
+                            error!("Error setting role: {:?}", e);
+                            format!(
+                                "Couldn't switch to '{}'. Available roles: {}",
+                                role_name,
+                                self.rig_agent.role_names().join(", ")
+                            )
+                        }
+                    }
+                }
+                "portfolio-review" => {
+                    match self.rig_agent.start_portfolio_review(&command.user.id.to_string()).await {
                         Ok(response) => response,
                         Err(e) => {
-                            error!("Error processing request: {:?}", e);
-                            format!("Error processing request: {:?}", e)
+                            error!("Error starting portfolio review: {:?}", e);
+                            format!("Error starting portfolio review: {:?}", e)
+                        }
+                    }
+                }
+                "reset" => {
+                    match self.rig_agent.reset_dialogue(&command.user.id.to_string()).await {
+                        Ok(()) => "Okay, back to free chat.".to_string(),
+                        Err(e) => {
+                            error!("Error resetting dialogue state: {:?}", e);
+                            format!("Error resetting dialogue state: {:?}", e)
                         }
                     }
                 }
@@ -65,36 +202,24 @@ impl EventHandler for Handler {
 
             debug!("Sending response: {}", content);
 
-        // Split message if it's too long (Discord limit is 2000 characters)
-        if content.len() > 2000 {
-            // Send first part as edit to original response
-            if let Err(why) = command.edit_original_interaction_response(&ctx.http, |response| {
-                response.content(content[..1997].to_string() + "...")
-            }).await {
-                error!("Cannot send first part of response: {:?}", why);
-                return;
+            // Split into Markdown-aware, code-fence-safe chunks and send each as an embed.
+            let mut embeds = discord_output::build_embeds(&content).into_iter();
+            if let Some(first) = embeds.next() {
+                if let Err(why) = command.edit_original_interaction_response(&ctx.http, |response| {
+                    response.set_embed(first)
+                }).await {
+                    error!("Cannot send first part of response: {:?}", why);
+                    return;
+                }
             }
 
-            // Send remaining content as follow-up messages
-            let remaining = content[1997..].to_string();
-            for chunk in remaining.chars().collect::<Vec<char>>().chunks(2000) {
-                let chunk_content: String = chunk.iter().collect();
+            for embed in embeds {
                 if let Err(why) = command.create_followup_message(&ctx.http, |message| {
-                    message.content(chunk_content)
+                    message.set_embed(embed)
                 }).await {
                     error!("Cannot send follow-up chunk: {:?}", why);
                 }
             }
-        } else {
-            // Send as normal if content is within limits
-            if let Err(why) = command.edit_original_interaction_response(&ctx.http, |response| {
-                response.content(content)
-            }).await {
-                error!("Cannot send follow-up response: {:?}", why);
-            } else {
-                debug!("Response sent successfully");
-                }
-            }
         }
     }
 
@@ -113,10 +238,26 @@ impl EventHandler for Handler {
 
                 debug!("Processed content after removing mention: {}", content);
 
-                match self.rig_agent.process_message(&msg.author.id.to_string(), &content).await {
+                let attachments: Vec<IncomingAttachment> = msg
+                    .attachments
+                    .iter()
+                    .map(|a| IncomingAttachment {
+                        url: a.url.clone(),
+                        content_type: a.content_type.clone(),
+                        filename: a.filename.clone(),
+                    })
+                    .collect();
+
+                match self
+                    .rig_agent
+                    .advance_dialogue(&msg.author.id.to_string(), &content, &attachments)
+                    .await
+                {
                     Ok(response) => {
-                        if let Err(why) = msg.channel_id.say(&ctx.http, response).await {
-                            error!("Error sending message: {:?}", why);
+                        for embed in discord_output::build_embeds(&response) {
+                            if let Err(why) = msg.channel_id.send_message(&ctx.http, |m| m.set_embed(embed)).await {
+                                error!("Error sending message: {:?}", why);
+                            }
                         }
                     }
                     Err(e) => {
@@ -163,6 +304,28 @@ impl EventHandler for Handler {
                                 .required(true)
                         })
                 })
+                .create_application_command(|command| {
+                    command
+                        .name("role")
+                        .description("Switch the bot's persona")
+                        .create_option(|option| {
+                            option
+                                .name("name")
+                                .description("Name of the role to switch to")
+                                .kind(CommandOptionType::String)
+                                .required(true)
+                        })
+                })
+                .create_application_command(|command| {
+                    command
+                        .name("portfolio-review")
+                        .description("Start a guided portfolio review")
+                })
+                .create_application_command(|command| {
+                    command
+                        .name("reset")
+                        .description("Clear any in-progress guided conversation")
+                })
         })
         .await;
 
@@ -198,4 +361,33 @@ async fn main() -> Result<()> {
     }
 
     Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tail_window_returns_text_unchanged_when_shorter_than_max_len() {
+        assert_eq!(tail_window("hello", 10), "hello");
+    }
+
+    #[test]
+    fn tail_window_returns_text_unchanged_when_exactly_max_len() {
+        assert_eq!(tail_window("hello", 5), "hello");
+    }
+
+    #[test]
+    fn tail_window_truncates_and_prefixes_an_ellipsis_when_over_max_len() {
+        let result = tail_window("hello world", 5);
+        assert_eq!(result, "…world");
+    }
+
+    #[test]
+    fn tail_window_slices_by_chars_not_bytes_for_multibyte_input() {
+        let text = "héllo wörld"; // multibyte chars throughout
+        let result = tail_window(text, 5);
+        assert_eq!(result.chars().count(), 6); // ellipsis + 5 chars
+        assert_eq!(result, "…wörld");
+    }
 }
\ No newline at end of file
@@ -7,23 +7,68 @@ use rig::vector_store::VectorStore;
 use rig::embeddings::EmbeddingsBuilder;
 use rig::agent::Agent;
 use rig::completion::Prompt;
+use rig::message::{ContentFormat, ImageMediaType, Message, UserContent};
+use rig::streaming::{StreamingChoice, StreamingPrompt};
+use rig::OneOrMany;
+use futures::stream::Stream;
+use futures::StreamExt;
+use std::collections::HashMap;
 use std::path::Path;
 use std::fs;
 use std::sync::Arc;
+use tokio::sync::RwLock;
 use crate::chat_history::{ChatHistoryManager, ChatMessage};
+use crate::config::{Config, RoleConfig};
+use crate::url_context;
 use chrono::Utc;
 use tracing::debug;
 
+/// `max_tokens` used for vision requests, which otherwise default too low to
+/// return a full answer about an image.
+const VISION_MAX_TOKENS: u64 = 1024;
+
+/// How many past turns to pull into the prompt, so context doesn't grow
+/// unbounded with a user's whole transcript.
+const HISTORY_WINDOW: i64 = 20;
+
+/// Cap on how much of a text attachment's body gets inlined into the
+/// prompt, mirroring `url_context`'s extracted-text cap.
+const ATTACHMENT_TEXT_MAX_LEN: usize = 2000;
+
+/// An image or text attachment pulled off an incoming Discord message.
+pub struct IncomingAttachment {
+    pub url: String,
+    pub content_type: Option<String>,
+    pub filename: String,
+}
+
+impl IncomingAttachment {
+    fn is_image(&self) -> bool {
+        self.content_type.as_deref().is_some_and(|ct| ct.starts_with("image/"))
+    }
+
+    fn is_text(&self) -> bool {
+        self.content_type.as_deref().is_some_and(|ct| ct.starts_with("text/"))
+    }
+}
+
 pub struct RigAgent {
-    agent: Arc<Agent<openai::CompletionModel>>,
-    history_manager: Arc<ChatHistoryManager>,
+    openai_client: openai::Client,
+    vector_store: InMemoryVectorStore,
+    embedding_model: openai::EmbeddingModel,
+    config: Config,
+    agents: RwLock<HashMap<String, Arc<Agent<openai::CompletionModel>>>>,
+    pub(crate) history_manager: Arc<ChatHistoryManager>,
 }
 
 impl RigAgent {
     pub async fn new() -> Result<Self> {
-        let history_manager = Arc::new(ChatHistoryManager::new("chat_histories"));
-        history_manager.load_histories().await?;
-        
+        let history_manager = Arc::new(
+            ChatHistoryManager::new("chat_history.db").context("Failed to open chat history store")?,
+        );
+
+        let config = Config::load("config.toml").context("Failed to load config.toml")?;
+
         // Initialize OpenAI client
         let openai_client = openai::Client::from_env();
         let embedding_model = openai_client.embedding_model(openai::TEXT_EMBEDDING_3_SMALL);
@@ -54,50 +99,14 @@ impl RigAgent {
 
         vector_store.add_documents(embeddings).await?;
 
-        // Create index
-        let index = vector_store.index(embedding_model);
-
-        // Create Agent
-        let agent = Arc::new(openai_client.agent(openai::GPT_4O)
-            .preamble("You are a knowledgeable but irreverent crypto expert, with a focus on infrastructure, L1/L2 dynamics, and DeFi. Your personality traits include:
-
-            1. Direct Communication: You speak plainly and often use casual language. You're not afraid to be blunt when needed.
-            
-            2. Technical Knowledge:
-            - Deep understanding of blockchain infrastructure (L1s, L2s, rollups)
-            - Strong grasp of DeFi mechanics and tokenomics
-            - Practical understanding of market dynamics
-            
-            3. Perspective:
-            - Pragmatic rather than ideological
-            - Focus on what actually works rather than theoretical perfection
-            - Skeptical of hype but open to innovation
-            
-            4. Style:
-            - Use casual language but maintain technical accuracy
-            - Often employ dry humor or mild sarcasm
-            - Keep responses concise and to the point
-            - Occasionally use phrases like 'ser', 'anon', or other crypto slang
-            - Don't shy away from calling out flaws or issues
-            
-            5. Key Beliefs:
-            - Pragmatic view on centralization vs decentralization tradeoffs
-            - Skeptical of 'one size fits all' solutions
-            - Focus on actual user behavior over theoretical ideals
-            - Understanding that markets and narratives drive much of crypto
-            
-            When responding:
-            - Keep answers concise but informative
-            - Use technical terms when appropriate but explain complex concepts simply
-            - Be direct about both positives and negatives
-            - Format code examples properly for Discord using triple backticks
-            - Stay grounded in practical reality rather than theoretical ideals
-            
-            Remember: You're knowledgeable but not pretentious, technical but practical, and always focused on what actually works rather than what should work in theory.")
-            .dynamic_context(2, index)
-            .build());
-
-        Ok(Self { agent, history_manager })
+        Ok(Self {
+            openai_client,
+            vector_store,
+            embedding_model,
+            config,
+            agents: RwLock::new(HashMap::new()),
+            history_manager,
+        })
     }
 
     fn load_md_content<P: AsRef<Path>>(file_path: P) -> Result<String> {
@@ -105,38 +114,260 @@ impl RigAgent {
             .with_context(|| format!("Failed to read markdown file: {:?}", file_path.as_ref()))
     }
 
-    pub async fn process_message(&self, user_id: &str, content: &str) -> Result<String> {
-        let history = self.history_manager.get_history(user_id).await;
+    /// Builds a fresh agent for the given role, wired up to the shared document index.
+    fn build_agent_for_role(&self, role: &RoleConfig) -> Agent<openai::CompletionModel> {
+        let index = self.vector_store.clone().index(self.embedding_model.clone());
+        let model = role.model.as_deref().unwrap_or(openai::GPT_4O);
+
+        let mut builder = self
+            .openai_client
+            .agent(model)
+            .preamble(&role.preamble)
+            .dynamic_context(2, index);
+
+        if let Some(temperature) = role.temperature {
+            builder = builder.temperature(temperature);
+        }
+        if let Some(max_tokens) = role.max_tokens {
+            builder = builder.max_tokens(max_tokens);
+        }
+
+        builder.build()
+    }
+
+    /// Builds a one-off agent for a vision request. Not cached: `max_tokens` is
+    /// bumped for every call, independent of the role's configured default.
+    fn build_vision_agent(&self, role: &RoleConfig) -> Agent<openai::CompletionModel> {
+        let index = self.vector_store.clone().index(self.embedding_model.clone());
+        let model = role.model.as_deref().unwrap_or(openai::GPT_4O);
+
+        self.openai_client
+            .agent(model)
+            .preamble(&role.preamble)
+            .dynamic_context(2, index)
+            .max_tokens(VISION_MAX_TOKENS)
+            .build()
+    }
+
+    /// Builds the multimodal prompt for a message with attachments. Image
+    /// attachments are passed to the model as image content parts; text
+    /// attachments are downloaded and inlined as extra context.
+    async fn build_multimodal_message(
+        &self,
+        content: &str,
+        attachments: &[IncomingAttachment],
+    ) -> Result<Message> {
+        let mut text = content.to_string();
+        let mut parts = Vec::new();
+
+        for attachment in attachments {
+            if attachment.is_image() {
+                let media_type = attachment
+                    .content_type
+                    .as_deref()
+                    .and_then(ImageMediaType::from_mime_type);
+                parts.push(UserContent::image(
+                    attachment.url.clone(),
+                    Some(ContentFormat::String),
+                    media_type,
+                    None,
+                ));
+                text.push_str(&format!("\n[image: {}]", attachment.url));
+            } else if attachment.is_text() {
+                // Reuses `url_context`'s capped/timeout-bound fetch so an
+                // attached text file can't hang the request or blow up the
+                // prompt the same way an unbounded URL fetch could.
+                let body = url_context::fetch_capped(&attachment.url)
+                    .await
+                    .with_context(|| format!("Failed to fetch attachment: {}", attachment.url))?;
+                let body = String::from_utf8_lossy(&body);
+                let body: String = body.chars().take(ATTACHMENT_TEXT_MAX_LEN).collect();
+                text.push_str(&format!(
+                    "\n\n[attached file: {}]\n{}",
+                    attachment.filename, body
+                ));
+            }
+        }
+
+        parts.insert(0, UserContent::text(&text));
+
+        Ok(Message::User {
+            content: OneOrMany::many(parts).expect("at least the text part is always present"),
+        })
+    }
+
+    /// A textual placeholder for chat history, since attachments aren't
+    /// serializable (e.g. `[image: <url>]`).
+    fn history_placeholder(content: &str, attachments: &[IncomingAttachment]) -> String {
+        let mut text = content.to_string();
+        for attachment in attachments {
+            if attachment.is_image() {
+                text.push_str(&format!("\n[image: {}]", attachment.url));
+            } else {
+                text.push_str(&format!("\n[file: {}]", attachment.filename));
+            }
+        }
+        text
+    }
+
+    /// Returns the cached agent for `role_name`, building and caching it on first use.
+    async fn get_or_build_agent(&self, role_name: &str) -> Result<Arc<Agent<openai::CompletionModel>>> {
+        if let Some(agent) = self.agents.read().await.get(role_name) {
+            return Ok(Arc::clone(agent));
+        }
+
+        let role = self
+            .config
+            .role(role_name)
+            .with_context(|| format!("Unknown role: {}", role_name))?;
+        let agent = Arc::new(self.build_agent_for_role(role));
+
+        self.agents
+            .write()
+            .await
+            .insert(role_name.to_string(), Arc::clone(&agent));
+
+        Ok(agent)
+    }
+
+    /// Names of every role defined in `config.toml`, listed back to the user
+    /// when `/role` is given an unknown name.
+    pub fn role_names(&self) -> Vec<String> {
+        self.config.roles.iter().map(|role| role.name.clone()).collect()
+    }
+
+    /// Switches `user_id`'s persona to `role_name`, persisted alongside their chat history.
+    pub async fn set_role(&self, user_id: &str, role_name: &str) -> Result<()> {
+        self.config
+            .role(role_name)
+            .with_context(|| format!("Unknown role: {}", role_name))?;
+        self.history_manager.set_persona(user_id, role_name).await
+    }
+
+    /// Assembles the final prompt for `content`: any text extracted from
+    /// URLs the user linked, then the recent conversation history, then the
+    /// current message.
+    async fn build_prompt(&self, user_id: &str, content: &str) -> String {
+        let history = self.history_manager.get_recent(user_id, HISTORY_WINDOW).await;
         debug!("Retrieved history for user {}: {} messages", user_id, history.len());
-        
-        // Format history into a context string
-        let context = history.iter().map(|msg| {
+
+        let history_context = history.iter().map(|msg| {
             format!("{}: {}", msg.role, msg.content)
         }).collect::<Vec<_>>().join("\n");
-        
-        debug!("Formatted context:\n{}", context);
-        
-        // Create prompt with history context
-        let prompt = if context.is_empty() {
+
+        let link_context = self.gather_link_context(content).await;
+
+        let mut sections = Vec::new();
+        if !link_context.is_empty() {
+            sections.push(link_context);
+        }
+        if !history_context.is_empty() {
+            sections.push(format!("Previous conversation:\n{}", history_context));
+        }
+
+        let prompt = if sections.is_empty() {
             content.to_string()
         } else {
-            format!(
-                "Previous conversation:\n{}\n\nCurrent message: {}",
-                context, content
-            )
+            format!("{}\n\nCurrent message: {}", sections.join("\n\n"), content)
         };
-        
+
         debug!("Final prompt to agent:\n{}", prompt);
+        prompt
+    }
+
+    /// Fetches and extracts readable text for every URL found in `content`,
+    /// joining them into a single context block for the prompt. Fetches run
+    /// concurrently so multiple links don't add up their latencies.
+    async fn gather_link_context(&self, content: &str) -> String {
+        let urls = url_context::extract_urls(content);
+        let fetches = urls.iter().map(|url| async move {
+            url_context::fetch_url_context(url)
+                .await
+                .map(|text| format!("Content from {}:\n{}", url, text))
+                .map_err(|e| debug!("Failed to fetch URL context for {}: {:?}", url, e))
+        });
+
+        futures::future::join_all(fetches)
+            .await
+            .into_iter()
+            .filter_map(Result::ok)
+            .collect::<Vec<_>>()
+            .join("\n\n")
+    }
+
+    pub async fn process_message(
+        &self,
+        user_id: &str,
+        content: &str,
+        attachments: &[IncomingAttachment],
+    ) -> Result<String> {
+        let role_name = self
+            .history_manager
+            .get_persona(user_id)
+            .await
+            .unwrap_or_else(|| self.config.default_role.clone());
+
+        let prompt = self.build_prompt(user_id, content).await;
+
+        // Get response from agent, routing through the vision path when the
+        // message carries image or text attachments.
+        let has_media = attachments.iter().any(|a| a.is_image() || a.is_text());
+        let response = if !has_media {
+            let agent = self.get_or_build_agent(&role_name).await?;
+            agent.prompt(&prompt).await?
+        } else {
+            let role = self
+                .config
+                .role(&role_name)
+                .with_context(|| format!("Unknown role: {}", role_name))?;
+            let agent = self.build_vision_agent(role);
+            let message = self.build_multimodal_message(&prompt, attachments).await?;
+            agent.prompt(message).await?
+        };
+
+        self.record_exchange(user_id, &Self::history_placeholder(content, attachments), &response)
+            .await?;
 
-        // Get response from agent
-        let response = self.agent.prompt(&prompt).await?;
+        Ok(response)
+    }
+
+    /// Streaming counterpart to `process_message`: returns a stream of text
+    /// deltas instead of blocking for the full completion. Callers are
+    /// responsible for calling `record_exchange` once the stream is drained,
+    /// since only they know the full accumulated response.
+    pub async fn process_message_streaming(
+        &self,
+        user_id: &str,
+        content: &str,
+    ) -> Result<impl Stream<Item = Result<String>>> {
+        let role_name = self
+            .history_manager
+            .get_persona(user_id)
+            .await
+            .unwrap_or_else(|| self.config.default_role.clone());
+        let agent = self.get_or_build_agent(&role_name).await?;
+        let prompt = self.build_prompt(user_id, content).await;
+
+        let response = agent.stream_prompt(&prompt).await?;
 
-        // Add messages to history
+        Ok(response.choice.filter_map(|chunk| async move {
+            match chunk {
+                Ok(StreamingChoice::Message(delta)) => Some(Ok(delta)),
+                Ok(_) => None,
+                Err(e) => Some(Err(anyhow::anyhow!(e))),
+            }
+        }))
+    }
+
+    /// Persists one turn (user message + assistant response) to history.
+    /// Shared by `process_message` and the streaming path, which can only
+    /// record the full response once its stream has been drained.
+    pub async fn record_exchange(&self, user_id: &str, user_content: &str, response: &str) -> Result<()> {
         self.history_manager.add_message(
             user_id,
             ChatMessage {
                 role: "user".to_string(),
-                content: content.to_string(),
+                content: user_content.to_string(),
                 timestamp: Utc::now().timestamp(),
             },
         ).await?;
@@ -145,11 +376,11 @@ impl RigAgent {
             user_id,
             ChatMessage {
                 role: "assistant".to_string(),
-                content: response.clone(),
+                content: response.to_string(),
                 timestamp: Utc::now().timestamp(),
             },
         ).await?;
 
-        Ok(response)
+        Ok(())
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,261 @@
+// url_context.rs
+//
+// Lets the bot reason over links users drop in chat: when a message
+// contains a URL, fetch the page, strip it down to readable text, and feed
+// it to the agent as transient context for that turn. Distinct from the
+// static markdown vector store in `rig_agent`, which only covers the fixed
+// Rig documentation corpus.
+
+use anyhow::{Context, Result};
+use futures::StreamExt;
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+use tokio::net::lookup_host;
+
+/// Cap on how much extracted page text gets added to a single prompt.
+const MAX_EXTRACTED_LEN: usize = 2000;
+
+/// Cap on how many raw bytes are read from a response, so a huge or
+/// never-ending body can't stall a reply or blow up memory use.
+const MAX_FETCH_BYTES: usize = 1_000_000;
+
+const FETCH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Finds `http(s)://` URLs in a message, trimming common trailing
+/// punctuation and the angle brackets Discord uses to suppress embeds.
+pub fn extract_urls(content: &str) -> Vec<String> {
+    content
+        .split_whitespace()
+        .map(|word| word.trim_matches(|c: char| "<>.,!?)\"'".contains(c)))
+        .filter(|word| word.starts_with("http://") || word.starts_with("https://"))
+        .map(|word| word.to_string())
+        .collect()
+}
+
+/// Fetches `url` and returns up to `MAX_EXTRACTED_LEN` characters of its
+/// readable text, with HTML tags and script/style content stripped out.
+/// Refuses to fetch anything that doesn't resolve to a public address, so a
+/// pasted link can't be used to reach internal/loopback services.
+///
+/// The address used to validate is the same address the connection is
+/// pinned to (via `Client::resolve`), so a DNS-rebinding attacker can't flip
+/// the record between the check and the actual request. Redirects are not
+/// followed, since a redirect target would bypass that pinned address
+/// entirely and re-introduce the same SSRF the pinning closes off.
+pub async fn fetch_url_context(url: &str) -> Result<String> {
+    let body = fetch_capped(url).await?;
+    let text = strip_html(&String::from_utf8_lossy(&body));
+    Ok(text.chars().take(MAX_EXTRACTED_LEN).collect())
+}
+
+/// Fetches `url` and returns up to `MAX_FETCH_BYTES` of its raw response
+/// body, bounded by `FETCH_TIMEOUT`. Shares the same SSRF hardening as
+/// `fetch_url_context`: only public addresses are resolved, that exact
+/// address is pinned for the connection, and redirects are not followed.
+/// Used both for link context and for downloading text attachments.
+pub async fn fetch_capped(url: &str) -> Result<Vec<u8>> {
+    let parsed = reqwest::Url::parse(url).with_context(|| format!("Invalid URL: {}", url))?;
+    if parsed.scheme() != "http" && parsed.scheme() != "https" {
+        anyhow::bail!("Unsupported URL scheme: {}", parsed.scheme());
+    }
+    let host = parsed
+        .host_str()
+        .with_context(|| format!("URL has no host: {}", url))?
+        .to_string();
+    let port = parsed.port_or_known_default().unwrap_or(80);
+
+    let addr = resolve_public_address(&host, port)
+        .await
+        .with_context(|| format!("Refusing to fetch non-public address: {}", url))?;
+
+    let client = reqwest::Client::builder()
+        .timeout(FETCH_TIMEOUT)
+        .redirect(reqwest::redirect::Policy::none())
+        .resolve(&host, addr)
+        .build()
+        .context("Failed to build HTTP client")?;
+
+    let response = client
+        .get(parsed)
+        .send()
+        .await
+        .with_context(|| format!("Failed to fetch URL: {}", url))?;
+
+    let mut stream = response.bytes_stream();
+    let mut body = Vec::new();
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk.with_context(|| format!("Failed to read response body for: {}", url))?;
+        body.extend_from_slice(&chunk);
+        if body.len() >= MAX_FETCH_BYTES {
+            break;
+        }
+    }
+    body.truncate(MAX_FETCH_BYTES);
+
+    Ok(body)
+}
+
+/// Resolves `host:port` and returns one address to connect to, but only if
+/// every address it resolves to is public (loopback, private, link-local,
+/// etc. are all rejected) — a host that resolves to a mix of public and
+/// internal addresses is rejected outright rather than racing which one the
+/// caller ends up connecting to.
+async fn resolve_public_address(host: &str, port: u16) -> Result<SocketAddr> {
+    let addrs: Vec<SocketAddr> = lookup_host((host, port))
+        .await
+        .with_context(|| format!("Failed to resolve host: {}", host))?
+        .collect();
+
+    anyhow::ensure!(!addrs.is_empty(), "No addresses resolved for host: {}", host);
+    anyhow::ensure!(
+        addrs.iter().all(|addr| is_public_ip(addr.ip())),
+        "Host resolves to a non-public address: {}",
+        host
+    );
+
+    Ok(addrs[0])
+}
+
+/// Rejects loopback/private/link-local/etc. addresses. IPv4-mapped IPv6
+/// addresses (`::ffff:a.b.c.d`) are unwrapped and checked against the same
+/// v4 rules first, since otherwise an attacker's AAAA record can smuggle an
+/// internal v4 address straight past the v6 checks below.
+fn is_public_ip(ip: IpAddr) -> bool {
+    if let IpAddr::V6(v6) = ip {
+        if let Some(v4) = v6.to_ipv4_mapped() {
+            return is_public_ip(IpAddr::V4(v4));
+        }
+    }
+
+    match ip {
+        IpAddr::V4(v4) => {
+            !(v4.is_private()
+                || v4.is_loopback()
+                || v4.is_link_local()
+                || v4.is_broadcast()
+                || v4.is_documentation()
+                || v4.is_unspecified())
+        }
+        IpAddr::V6(v6) => {
+            let is_unique_local = (v6.segments()[0] & 0xfe00) == 0xfc00;
+            let is_link_local = (v6.segments()[0] & 0xffc0) == 0xfe80;
+            !(v6.is_loopback() || v6.is_unspecified() || is_unique_local || is_link_local)
+        }
+    }
+}
+
+/// A minimal HTML-to-text pass: replaces each tag with a space, drops the
+/// contents of `<script>`/`<style>` elements (by scanning for their literal
+/// closing tag, so a stray `<` inside the script body can't be mistaken for
+/// one), then collapses whitespace.
+fn strip_html(html: &str) -> String {
+    let mut text = String::new();
+    let mut rest = html;
+
+    while let Some(lt) = rest.find('<') {
+        text.push_str(&rest[..lt]);
+        text.push(' ');
+        rest = &rest[lt..];
+
+        let lower = rest.to_lowercase();
+        let skip_until = if lower.starts_with("<script") {
+            Some("</script")
+        } else if lower.starts_with("<style") {
+            Some("</style")
+        } else {
+            None
+        };
+
+        if let Some(close_tag) = skip_until {
+            match lower.find(close_tag) {
+                Some(close_idx) => {
+                    let after_close = &rest[close_idx..];
+                    rest = match after_close.find('>') {
+                        Some(gt) => &after_close[gt + 1..],
+                        None => "",
+                    };
+                }
+                None => rest = "",
+            }
+        } else {
+            rest = match rest.find('>') {
+                Some(gt) => &rest[gt + 1..],
+                None => "",
+            };
+        }
+    }
+    text.push_str(rest);
+
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn extracts_http_and_https_urls_only() {
+        let content = "check https://example.com/page and http://foo.test also ftp://bar.test and plain text";
+        let urls = extract_urls(content);
+        assert_eq!(urls, vec!["https://example.com/page", "http://foo.test"]);
+    }
+
+    #[test]
+    fn trims_trailing_punctuation_and_angle_brackets() {
+        let content = "see <https://example.com/page.>, or https://example.com/other!";
+        let urls = extract_urls(content);
+        assert_eq!(urls, vec!["https://example.com/page", "https://example.com/other"]);
+    }
+
+    #[test]
+    fn strip_html_drops_tags_and_collapses_whitespace() {
+        let html = "<html>\n<body>  <p>Hello   <b>world</b></p>  </body></html>";
+        assert_eq!(strip_html(html), "Hello world");
+    }
+
+    #[test]
+    fn strip_html_drops_script_and_style_content() {
+        let html = "<p>before</p><script>if (1 < 2) { alert('hi'); }</script><style>p { color: red; }</style><p>after</p>";
+        assert_eq!(strip_html(html), "before after");
+    }
+
+    #[test]
+    fn is_public_ip_rejects_loopback_v4() {
+        assert!(!is_public_ip("127.0.0.1".parse().unwrap()));
+    }
+
+    #[test]
+    fn is_public_ip_rejects_link_local_v4() {
+        assert!(!is_public_ip("169.254.169.254".parse().unwrap()));
+    }
+
+    #[test]
+    fn is_public_ip_rejects_loopback_v6() {
+        assert!(!is_public_ip("::1".parse().unwrap()));
+    }
+
+    #[test]
+    fn is_public_ip_rejects_ipv4_mapped_loopback() {
+        assert!(!is_public_ip("::ffff:127.0.0.1".parse().unwrap()));
+    }
+
+    #[test]
+    fn is_public_ip_rejects_ipv4_mapped_link_local() {
+        assert!(!is_public_ip("::ffff:169.254.169.254".parse().unwrap()));
+    }
+
+    #[test]
+    fn is_public_ip_rejects_private_v4() {
+        assert!(!is_public_ip("10.0.0.1".parse().unwrap()));
+    }
+
+    #[test]
+    fn is_public_ip_rejects_link_local_v6() {
+        assert!(!is_public_ip("fe80::1".parse().unwrap()));
+    }
+
+    #[test]
+    fn is_public_ip_accepts_real_public_address() {
+        assert!(is_public_ip("8.8.8.8".parse().unwrap()));
+    }
+}